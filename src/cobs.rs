@@ -0,0 +1,131 @@
+//! Consistent Overhead Byte Stuffing (COBS) helpers backing
+//! [`HidUart::send_packet`](../struct.HidUart.html#method.send_packet) and
+//! [`HidUart::recv_packet`](../struct.HidUart.html#method.recv_packet).
+//!
+//! COBS removes every zero byte from a payload so that a single `0x00` can
+//! be used unambiguously as a frame delimiter over the byte stream.
+
+use crate::error::*;
+
+/// Encodes `data` as a single COBS block terminated by a `0x00` delimiter.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 254 + 2);
+    let mut code_pos = out.len();
+    out.push(0); // placeholder, patched once the block length is known
+    let mut code: u8 = 1;
+
+    for &byte in data {
+        if byte == 0 {
+            out[code_pos] = code;
+            code_pos = out.len();
+            out.push(0);
+            code = 1;
+        } else {
+            out.push(byte);
+            code += 1;
+            if code == 0xFF {
+                out[code_pos] = code;
+                code_pos = out.len();
+                out.push(0);
+                code = 1;
+            }
+        }
+    }
+    out[code_pos] = code;
+    out.push(0x00);
+    out
+}
+
+/// Decodes a single COBS block (without its trailing `0x00` delimiter).
+///
+/// Returns `ErrorKind::FramingError` if `data` is not a well-formed block.
+pub fn decode(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let code = data[pos] as usize;
+        if code == 0 {
+            return Err(ErrorKind::FramingError.into());
+        }
+        pos += 1;
+
+        let run_len = code - 1;
+        if pos + run_len > data.len() {
+            return Err(ErrorKind::FramingError.into());
+        }
+        out.extend_from_slice(&data[pos..pos + run_len]);
+        pos += run_len;
+
+        if code != 0xFF && pos < data.len() {
+            out.push(0);
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes `data` and strips the trailing `0x00` delimiter, since
+    /// `decode` expects a block without it.
+    fn encode_block(data: &[u8]) -> Vec<u8> {
+        let mut framed = encode(data);
+        assert_eq!(framed.pop(), Some(0x00));
+        framed
+    }
+
+    fn assert_round_trips(data: &[u8]) {
+        let decoded = decode(&encode_block(data)).expect("well-formed block should decode");
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn round_trips_typical_payload() {
+        assert_round_trips(b"hello, world!");
+    }
+
+    #[test]
+    fn round_trips_payload_with_embedded_zeros() {
+        assert_round_trips(&[0x00, 0x01, 0x00, 0x00, 0xFF, 0x00]);
+    }
+
+    #[test]
+    fn round_trips_empty_payload() {
+        assert_round_trips(&[]);
+    }
+
+    #[test]
+    fn round_trips_254_byte_boundary() {
+        // 254 non-zero bytes is exactly enough to force a block split
+        // (code reaches 0xFF), so this exercises the boundary from both
+        // sides.
+        assert_round_trips(&vec![0x01; 254]);
+        assert_round_trips(&vec![0x01; 255]);
+    }
+
+    #[test]
+    fn encode_terminates_with_zero_delimiter() {
+        let framed = encode(b"abc");
+        assert_eq!(framed.last(), Some(&0x00));
+    }
+
+    #[test]
+    fn decode_rejects_truncated_block() {
+        // Claims a 5-byte run but only 2 bytes follow.
+        let truncated = [0x06, 0x01, 0x02];
+        let err = decode(&truncated).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::FramingError));
+    }
+
+    #[test]
+    fn decode_rejects_embedded_zero_code() {
+        // A 0x00 code byte can only be the frame delimiter, never part of
+        // the block itself.
+        let malformed = [0x02, 0x01, 0x00];
+        let err = decode(&malformed).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::FramingError));
+    }
+}
@@ -8,5 +8,13 @@ error_chain! {
             description("write operation is time out")
             display("write operation is time out")
         }
+        FramingError {
+            description("malformed COBS frame")
+            display("malformed COBS frame")
+        }
+        InvalidGpioPin(pin: u8) {
+            description("GPIO pin out of range")
+            display("GPIO pin {} is out of range (must be 0-7)", pin)
+        }
     }
 }
@@ -0,0 +1,164 @@
+//! GPIO access and RS485 half-duplex support built on top of it.
+//!
+//! CP2110 exposes up to 8 GPIO pins that can be read, driven, or (as used
+//! by [`HidUart::enable_rs485`]) wired up to an RS485 transceiver's
+//! driver-enable line.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::*;
+use crate::{get_uart_status, HidUart, FEATURE_REPORT_LENGTH};
+
+const GETSET_GPIO_VALUES: u8 = 0x44; // Get/Set GPIO Latch
+const GETSET_GPIO_CONFIG: u8 = 0x02; // Get/Set GPIO Control (pin direction)
+
+/// Delay between transmit FIFO polls in
+/// [`wait_for_tx_drain`](HidUart::wait_for_tx_drain).
+const TX_DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Configuration for driving an RS485 transceiver's driver-enable (DE) pin
+/// from one of the CP2110's GPIO pins.
+///
+/// Install with [`HidUart::enable_rs485`]; once enabled, [`HidUart::write`]
+/// asserts `de_pin` before transmitting and deasserts it only after the
+/// transmit FIFO has drained and `turnaround` has elapsed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rs485Config {
+    /// GPIO pin (0-7) wired to the transceiver's DE/RE control line.
+    pub de_pin: u8,
+    /// `true` if driving the pin high enables the transmitter, `false` if
+    /// the transceiver is active-low.
+    pub assert_high: bool,
+    /// Extra delay after the transmit FIFO drains, covering the line's
+    /// turnaround time, before the DE pin is deasserted.
+    pub turnaround: Duration,
+}
+
+fn set_gpio_values(handle: &Arc<Mutex<hidapi::HidDevice>>, values: u8, mask: u8) -> Result<()> {
+    let mut buf: [u8; FEATURE_REPORT_LENGTH] = [0; FEATURE_REPORT_LENGTH];
+
+    buf[0] = GETSET_GPIO_VALUES;
+    buf[1] = values;
+    buf[2] = mask;
+    handle.lock().unwrap().send_feature_report(&buf[..])?;
+    Ok(())
+}
+
+/// Asserts `config.de_pin`, enabling the RS485 transceiver's driver.
+/// Shared by [`HidUart::write`] and [`UartTx::write`](crate::UartTx::write).
+pub(crate) fn assert_de(handle: &Arc<Mutex<hidapi::HidDevice>>, config: &Rs485Config) -> Result<()> {
+    let mask = 1u8 << config.de_pin;
+    let asserted = if config.assert_high { mask } else { 0 };
+    set_gpio_values(handle, asserted, mask)
+}
+
+/// Deasserts `config.de_pin`. Shared by [`HidUart::write`] and
+/// [`UartTx::write`](crate::UartTx::write).
+pub(crate) fn deassert_de(handle: &Arc<Mutex<hidapi::HidDevice>>, config: &Rs485Config) -> Result<()> {
+    let mask = 1u8 << config.de_pin;
+    let deasserted = if config.assert_high { 0 } else { mask };
+    set_gpio_values(handle, deasserted, mask)
+}
+
+/// Polls [`get_uart_status`] until the transmit FIFO reports empty,
+/// sleeping `TX_DRAIN_POLL_INTERVAL` between polls, then waits out
+/// `config.turnaround`.
+///
+/// Bounded by `write_timeout`, like every other blocking operation in this
+/// crate, so a stalled line or flaky device can't spin forever. Shared by
+/// [`HidUart::write`] and [`UartTx::write`](crate::UartTx::write).
+pub(crate) fn wait_for_tx_drain(
+    handle: &Arc<Mutex<hidapi::HidDevice>>,
+    config: &Rs485Config,
+    write_timeout: Duration,
+) -> Result<()> {
+    let start_time = Instant::now();
+    loop {
+        let status = get_uart_status(handle)?;
+        if status.tx_fifo_bytes == 0 {
+            break;
+        }
+        if start_time.elapsed() > write_timeout {
+            return Err(ErrorKind::WriteTimeout.into());
+        }
+        thread::sleep(TX_DRAIN_POLL_INTERVAL);
+    }
+    thread::sleep(config.turnaround);
+
+    Ok(())
+}
+
+impl HidUart {
+    /// Returns the current GPIO pin values as a bitmask (bit N = pin N).
+    pub fn get_gpio_values(&mut self) -> Result<u8> {
+        let mut buf: [u8; FEATURE_REPORT_LENGTH] = [0; FEATURE_REPORT_LENGTH];
+
+        buf[0] = GETSET_GPIO_VALUES;
+        self.handle.lock().unwrap().get_feature_report(&mut buf[..])?;
+        Ok(buf[1])
+    }
+
+    /// Sets the GPIO pins selected by `mask` to the corresponding bits of
+    /// `values`. Pins not selected by `mask` are left untouched.
+    pub fn set_gpio_values(&mut self, values: u8, mask: u8) -> Result<()> {
+        set_gpio_values(&self.handle, values, mask)
+    }
+
+    /// Configures which GPIO pins are outputs, via a bitmask (bit N = pin
+    /// N is an output).
+    pub fn set_gpio_direction(&mut self, outputs_mask: u8) -> Result<()> {
+        let mut buf: [u8; FEATURE_REPORT_LENGTH] = [0; FEATURE_REPORT_LENGTH];
+
+        buf[0] = GETSET_GPIO_CONFIG;
+        buf[1] = outputs_mask;
+        self.handle.lock().unwrap().send_feature_report(&buf[..])?;
+        Ok(())
+    }
+
+    /// Enables RS485 half-duplex mode, configuring `config.de_pin` as a
+    /// GPIO output and deasserting it.
+    ///
+    /// Returns `ErrorKind::InvalidGpioPin` if `config.de_pin` is outside the
+    /// valid 0-7 range.
+    pub fn enable_rs485(&mut self, config: Rs485Config) -> Result<()> {
+        if config.de_pin > 7 {
+            return Err(ErrorKind::InvalidGpioPin(config.de_pin).into());
+        }
+
+        let mask = 1u8 << config.de_pin;
+        self.set_gpio_direction(mask)?;
+        let deasserted = if config.assert_high { 0 } else { mask };
+        self.set_gpio_values(deasserted, mask)?;
+        self.rs485 = Some(config);
+        Ok(())
+    }
+
+    /// Disables RS485 half-duplex mode; `write` no longer touches the DE
+    /// pin afterwards.
+    pub fn disable_rs485(&mut self) {
+        self.rs485 = None;
+    }
+
+    pub(crate) fn assert_de(&mut self) -> Result<()> {
+        if let Some(config) = self.rs485 {
+            assert_de(&self.handle, &config)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn deassert_de(&mut self) -> Result<()> {
+        if let Some(config) = self.rs485 {
+            deassert_de(&self.handle, &config)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn wait_for_tx_drain(&mut self) -> Result<()> {
+        if let Some(config) = self.rs485 {
+            wait_for_tx_drain(&self.handle, &config, self.write_timeout)?;
+        }
+        Ok(())
+    }
+}
@@ -6,14 +6,23 @@
 extern crate hidapi;
 #[macro_use]
 extern crate error_chain;
+#[cfg(feature = "embedded-io")]
+extern crate embedded_io;
 
 use std::cmp::min;
+use std::collections::VecDeque;
 use std::default::Default;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
+mod cobs;
 mod error;
 pub use error::Error;
 use error::*;
+mod gpio;
+pub use gpio::Rs485Config;
+mod split;
+pub use split::{UartRx, UartTx};
 
 const FEATURE_REPORT_LENGTH: usize = 64;
 const INTERRUPT_REPORT_LENGTH: usize = 64;
@@ -21,6 +30,10 @@ const INTERRUPT_REPORT_LENGTH: usize = 64;
 const GETSET_UART_ENABLE: u8 = 0x41; // Get Set Receive Status
 const PURGE_FIFOS: u8 = 0x43; // Purge FIFOs
 const GETSET_UART_CONFIG: u8 = 0x50; // Get Set UART Config
+const GET_UART_STATUS: u8 = 0x42; // Get UART Status
+
+const STATUS_PARITY_ERROR_MASK: u8 = 0x01;
+const STATUS_OVERRUN_ERROR_MASK: u8 = 0x02;
 
 const PURGE_TRANSMIT_MASK: u8 = 0x01;
 const PURGE_RECEIVE_MASK: u8 = 0x02;
@@ -117,15 +130,33 @@ impl Default for UartConfig {
     }
 }
 
+/// Snapshot of UART FIFO fill levels and line error flags, as returned by
+/// [`HidUart::get_uart_status`](struct.HidUart.html#method.get_uart_status).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct UartStatus {
+    /// Number of bytes currently queued in the transmit FIFO.
+    pub tx_fifo_bytes: u16,
+    /// Number of bytes currently queued in the receive FIFO.
+    pub rx_fifo_bytes: u16,
+    /// A parity error was detected on the line.
+    pub parity_error: bool,
+    /// An overrun error was detected on the line.
+    pub overrun_error: bool,
+    /// A break condition was detected on the line.
+    pub line_break: bool,
+}
+
 /// Wrapper around `hidapi::HidDevice` to provide UART control.
 pub struct HidUart {
-    handle: hidapi::HidDevice,
+    handle: Arc<Mutex<hidapi::HidDevice>>,
     read_timeout: Duration,
     write_timeout: Duration,
     rx_buffer: RxBuffer,
+    frame_buffer: Vec<u8>,
+    rs485: Option<Rs485Config>,
 }
 
-fn set_uart_enable(handle: &mut hidapi::HidDevice, enable: bool) -> Result<()> {
+fn set_uart_enable(handle: &Arc<Mutex<hidapi::HidDevice>>, enable: bool) -> Result<()> {
     let mut buf: [u8; FEATURE_REPORT_LENGTH] = [0; FEATURE_REPORT_LENGTH];
 
     buf[0] = GETSET_UART_ENABLE;
@@ -134,20 +165,143 @@ fn set_uart_enable(handle: &mut hidapi::HidDevice, enable: bool) -> Result<()> {
     } else {
         buf[1] = 0x00;
     }
-    handle.send_feature_report(&buf[..])?;
+    handle.lock().unwrap().send_feature_report(&buf[..])?;
     Ok(())
 }
 
+/// Transmits `data`, locking `handle` only for the duration of each HID
+/// report write. Shared by [`HidUart::write`] and [`UartTx::write`].
+pub(crate) fn write_report(
+    handle: &Arc<Mutex<hidapi::HidDevice>>,
+    write_timeout: Duration,
+    data: &[u8],
+) -> Result<()> {
+    let mut buf: [u8; INTERRUPT_REPORT_LENGTH];
+
+    let start_time = Instant::now();
+    for chunk in data.chunks(INTERRUPT_REPORT_LENGTH - 1) {
+        buf = [0; INTERRUPT_REPORT_LENGTH];
+        buf[0] = chunk.len() as u8;
+        buf[1..chunk.len() + 1].copy_from_slice(chunk);
+        handle.lock().unwrap().write(&buf[..])?;
+        if start_time.elapsed() > write_timeout {
+            return Err(ErrorKind::WriteTimeout.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies one HID report's worth of data into `data`/`rx_buffer`, polling
+/// `handle` with `poll_timeout_ms` (`hidapi`'s non-blocking value is `0`).
+///
+/// Returns `true` once `data` is full or a leftover tail had to be
+/// buffered, which is the signal for the blocking caller to stop polling.
+fn poll_report(
+    handle: &Arc<Mutex<hidapi::HidDevice>>,
+    rx_buffer: &mut RxBuffer,
+    data: &mut [u8],
+    num_bytes_read: &mut usize,
+    poll_timeout_ms: i32,
+) -> Result<bool> {
+    let data_free = data.len() - *num_bytes_read;
+    if data_free == 0 {
+        return Ok(true);
+    }
+
+    let mut buf: [u8; INTERRUPT_REPORT_LENGTH] = [0; INTERRUPT_REPORT_LENGTH];
+    let total_read = handle.lock().unwrap().read_timeout(&mut buf, poll_timeout_ms)?;
+    if total_read != 0 {
+        let report_len: usize = buf[0] as usize;
+        let copy_len = min(report_len, data_free);
+        data[*num_bytes_read..(*num_bytes_read + copy_len)].copy_from_slice(&buf[1..(copy_len + 1)]);
+        *num_bytes_read += copy_len;
+
+        // buffer the left overs
+        if copy_len < report_len {
+            let left = report_len - copy_len;
+            let start = 1 + copy_len;
+            let end = start + left;
+
+            rx_buffer.write(&buf[start..end]);
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Receives into `data`, blocking up to `read_timeout`. Shared by
+/// [`HidUart::read`] and [`UartRx::read`].
+pub(crate) fn read_report(
+    handle: &Arc<Mutex<hidapi::HidDevice>>,
+    read_timeout: Duration,
+    rx_buffer: &mut RxBuffer,
+    data: &mut [u8],
+) -> Result<usize> {
+    let mut num_bytes_read = rx_buffer.read(data);
+
+    let start_time = Instant::now();
+    loop {
+        if poll_report(handle, rx_buffer, data, &mut num_bytes_read, 1)? {
+            break;
+        }
+        if start_time.elapsed() > read_timeout {
+            break;
+        }
+    }
+
+    Ok(num_bytes_read)
+}
+
+/// Receives into `data` via a single non-blocking HID poll. Shared by
+/// [`HidUart::read_nonblocking`] and [`UartRx::read_nonblocking`].
+pub(crate) fn read_report_nonblocking(
+    handle: &Arc<Mutex<hidapi::HidDevice>>,
+    rx_buffer: &mut RxBuffer,
+    data: &mut [u8],
+) -> Result<usize> {
+    let mut num_bytes_read = rx_buffer.read(data);
+    poll_report(handle, rx_buffer, data, &mut num_bytes_read, 0)?;
+    Ok(num_bytes_read)
+}
+
+/// Queries the transmit/receive FIFO fill levels and line error flags.
+/// Shared by [`HidUart::get_uart_status`] and
+/// [`gpio::wait_for_tx_drain`](gpio::wait_for_tx_drain).
+pub(crate) fn get_uart_status(handle: &Arc<Mutex<hidapi::HidDevice>>) -> Result<UartStatus> {
+    let mut buf: [u8; FEATURE_REPORT_LENGTH] = [0; FEATURE_REPORT_LENGTH];
+
+    buf[0] = GET_UART_STATUS;
+    handle.lock().unwrap().get_feature_report(&mut buf[..])?;
+
+    let tx_fifo_bytes = u16::from(buf[1]) << 8 | u16::from(buf[2]);
+    let rx_fifo_bytes = u16::from(buf[3]) << 8 | u16::from(buf[4]);
+    let parity_error = buf[5] & STATUS_PARITY_ERROR_MASK != 0;
+    let overrun_error = buf[5] & STATUS_OVERRUN_ERROR_MASK != 0;
+    let line_break = buf[6] != 0;
+
+    Ok(UartStatus {
+        tx_fifo_bytes,
+        rx_fifo_bytes,
+        parity_error,
+        overrun_error,
+        line_break,
+    })
+}
+
 impl HidUart {
     /// Returns a new instance of `HidUart` from `hidapi::HidDevice`.
     ///
     /// The instance enables UART automatically.
     pub fn new(handle: hidapi::HidDevice) -> Result<HidUart> {
         let mut instance = HidUart {
-            handle,
+            handle: Arc::new(Mutex::new(handle)),
             read_timeout: Duration::from_millis(1000),
             write_timeout: Duration::from_millis(1000),
             rx_buffer: RxBuffer::new(),
+            frame_buffer: Vec::new(),
+            rs485: None,
         };
         instance.enable()?;
         Ok(instance)
@@ -175,12 +329,12 @@ impl HidUart {
 
     /// Enable UART.
     pub fn enable(&mut self) -> Result<()> {
-        set_uart_enable(&mut self.handle, true)
+        set_uart_enable(&self.handle, true)
     }
 
     /// Disable UART.
     pub fn disable(&mut self) -> Result<()> {
-        set_uart_enable(&mut self.handle, false)
+        set_uart_enable(&self.handle, false)
     }
 
     /// Returns UART state: `true` if UART is enabled, `false` otherwise.
@@ -188,7 +342,7 @@ impl HidUart {
         let mut buf: [u8; FEATURE_REPORT_LENGTH] = [0; FEATURE_REPORT_LENGTH];
 
         buf[0] = GETSET_UART_ENABLE;
-        self.handle.get_feature_report(&mut buf[..])?;
+        self.handle.lock().unwrap().get_feature_report(&mut buf[..])?;
         if buf[1] == 0x00 {
             Ok(false)
         } else {
@@ -227,7 +381,7 @@ impl HidUart {
             StopBits::Long => 0x01,
         };
 
-        self.handle.send_feature_report(&buf[..])?;
+        self.handle.lock().unwrap().send_feature_report(&buf[..])?;
         Ok(())
     }
 
@@ -236,7 +390,7 @@ impl HidUart {
         let mut buf: [u8; FEATURE_REPORT_LENGTH] = [0; FEATURE_REPORT_LENGTH];
 
         buf[0] = GETSET_UART_CONFIG;
-        self.handle.get_feature_report(&mut buf[..])?;
+        self.handle.lock().unwrap().get_feature_report(&mut buf[..])?;
 
         let baud_rate: u32 = u32::from(buf[1]) << 24
             | u32::from(buf[2]) << 16
@@ -278,6 +432,12 @@ impl HidUart {
         Ok(config)
     }
 
+    /// Returns the current transmit/receive FIFO fill levels and line
+    /// error flags.
+    pub fn get_uart_status(&mut self) -> Result<UartStatus> {
+        get_uart_status(&self.handle)
+    }
+
     /// Empties receiving and/or transmitting FIFO buffers.
     ///
     /// Flushes receiving FIFO buffer if `rx` is `true`.
@@ -292,123 +452,282 @@ impl HidUart {
 
             // also dump any buffered data
             self.rx_buffer.clear();
+            self.frame_buffer.clear();
         }
         if tx {
             buf[1] |= PURGE_TRANSMIT_MASK;
         }
-        self.handle.send_feature_report(&buf[..])?;
+        self.handle.lock().unwrap().send_feature_report(&buf[..])?;
 
         Ok(())
     }
 
     /// Transmit `data`.
+    ///
+    /// When [RS485 mode](#method.enable_rs485) is enabled, the driver-enable
+    /// GPIO pin is asserted before transmitting and deasserted only once the
+    /// transmit FIFO has drained, so callers don't need to toggle the line
+    /// themselves. The DE pin is deasserted even if transmitting or waiting
+    /// for the FIFO to drain fails, so a single failed write can't leave the
+    /// transceiver stuck in the wrong direction; the earliest error is the
+    /// one returned.
     pub fn write(&mut self, data: &[u8]) -> Result<()> {
-        let mut buf: [u8; INTERRUPT_REPORT_LENGTH];
-
-        let start_time = Instant::now();
-        for chunk in data.chunks(INTERRUPT_REPORT_LENGTH - 1) {
-            buf = [0; INTERRUPT_REPORT_LENGTH];
-            buf[0] = chunk.len() as u8;
-            buf[1..chunk.len() + 1].copy_from_slice(chunk);
-            self.handle.write(&buf[..])?;
-            if start_time.elapsed() > self.write_timeout {
-                return Err(ErrorKind::WriteTimeout.into());
-            }
+        if self.rs485.is_some() {
+            self.assert_de()?;
+            let write_result = self.write_raw(data);
+            let drain_result = self.wait_for_tx_drain();
+            let deassert_result = self.deassert_de();
+            return write_result.and(drain_result).and(deassert_result);
         }
 
-        Ok(())
+        self.write_raw(data)
+    }
+
+    fn write_raw(&mut self, data: &[u8]) -> Result<()> {
+        write_report(&self.handle, self.write_timeout, data)
     }
 
     /// Receive `data` and returns a number of read bytes.
     pub fn read(&mut self, data: &mut [u8]) -> Result<usize> {
-        // drain any buffered data
-        let mut num_bytes_read = self.rx_buffer.read(data);
+        read_report(&self.handle, self.read_timeout, &mut self.rx_buffer, data)
+    }
+
+    /// Drains buffered data and performs a single immediate, non-blocking
+    /// poll of the HID device, returning at once instead of waiting up to
+    /// `read_timeout`.
+    ///
+    /// Useful for event-loop style callers that want to pull whatever data
+    /// is already available without stalling the loop.
+    pub fn read_nonblocking(&mut self, data: &mut [u8]) -> Result<usize> {
+        read_report_nonblocking(&self.handle, &mut self.rx_buffer, data)
+    }
+
+    /// Returns the number of bytes currently buffered and available to
+    /// read without blocking.
+    pub fn bytes_available(&self) -> usize {
+        self.rx_buffer.len()
+    }
+
+    /// Sets the capacity, in bytes, of the internal receive ring buffer.
+    ///
+    /// If the buffer currently holds more bytes than the new capacity, the
+    /// oldest buffered bytes are dropped. A capacity of `0` disables
+    /// buffering entirely: the part of a HID report that doesn't fit in the
+    /// caller's destination slice is dropped.
+    pub fn set_rx_buffer_capacity(&mut self, capacity: usize) {
+        self.rx_buffer.set_capacity(capacity);
+    }
+
+    /// Returns the number of bytes ever dropped from the receive ring
+    /// buffer because it was full (or had zero capacity) when new data
+    /// arrived. Monotonically increasing; compare successive readings to
+    /// detect loss under bursty traffic instead of resetting it.
+    pub fn rx_overflow_count(&self) -> u64 {
+        self.rx_buffer.overflow_count()
+    }
+
+    /// Sends `payload` as a single COBS-framed packet, delimited by `0x00`.
+    pub fn send_packet(&mut self, payload: &[u8]) -> Result<()> {
+        let framed = cobs::encode(payload);
+        self.write(&framed)
+    }
 
-        // read from usb
-        let mut buf: [u8; INTERRUPT_REPORT_LENGTH];
-        let start_time = Instant::now();
+    /// Receives a single COBS-framed packet into `payload`, returning the
+    /// number of bytes written to it.
+    ///
+    /// Bytes read before the `0x00` delimiter arrives are buffered
+    /// internally across calls, since HID reports don't align to frame
+    /// boundaries. Returns `Ok(0)` without touching `payload` if no byte
+    /// arrives within `read_timeout` before a delimiter is seen; the
+    /// timeout applies to each byte read, not to the call as a whole.
+    pub fn recv_packet(&mut self, payload: &mut Vec<u8>) -> Result<usize> {
+        let mut byte = [0u8; 1];
         loop {
-            let data_free = data.len() - num_bytes_read;
-            if data_free > 0 {
-                buf = [0; INTERRUPT_REPORT_LENGTH];
-                let total_read = self.handle.read_timeout(&mut buf, 1)?;
-                if total_read != 0 {
-                    let report_len: usize = buf[0] as usize;
-                    let copy_len = min(report_len, data_free);
-                    data[num_bytes_read..(num_bytes_read + copy_len)]
-                        .copy_from_slice(&buf[1..(copy_len + 1)]);
-                    num_bytes_read += copy_len;
-
-                    // buffer the left overs
-                    if copy_len < report_len {
-                        let left = report_len - copy_len;
-                        let start = 1 + copy_len;
-                        let end = start + left;
-
-                        self.rx_buffer.write(&buf[start..end]);
-
-                        return Ok(num_bytes_read);
-                    }
-                }
-            } else {
-                break;
+            if self.read(&mut byte)? == 0 {
+                return Ok(0);
             }
-            if start_time.elapsed() > self.read_timeout {
-                break;
+
+            if byte[0] == 0x00 {
+                let result = cobs::decode(&self.frame_buffer);
+                self.frame_buffer.clear();
+                *payload = result?;
+                return Ok(payload.len());
             }
+
+            self.frame_buffer.push(byte[0]);
         }
+    }
+}
 
+fn to_io_error(err: Error) -> std::io::Error {
+    match err.kind() {
+        ErrorKind::WriteTimeout => std::io::Error::new(std::io::ErrorKind::TimedOut, err.to_string()),
+        _ => std::io::Error::new(std::io::ErrorKind::Other, err.to_string()),
+    }
+}
+
+impl std::io::Read for HidUart {
+    /// Reads bytes into `buf`, mapping a zero-byte result into
+    /// `ErrorKind::WouldBlock` when [`read_timeout`](#method.read_timeout)
+    /// is zero, since in that case `HidUart::read` returns after a single
+    /// short poll instead of waiting for more data.
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let num_bytes_read = HidUart::read(self, buf).map_err(to_io_error)?;
+        if num_bytes_read == 0 && self.read_timeout.is_zero() {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
         Ok(num_bytes_read)
     }
 }
 
+impl std::io::Write for HidUart {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        HidUart::write(self, buf).map_err(to_io_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_fifos(false, true).map_err(to_io_error)
+    }
+}
+
+#[cfg(feature = "embedded-io")]
+mod embedded_io_impl {
+    use super::HidUart;
+    use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write};
+
+    /// Wraps `std::io::Error` so it can serve as `embedded-io`'s associated
+    /// `Error` type, which requires an `embedded_io::Error` impl that plain
+    /// `std::io::Error` doesn't provide.
+    #[derive(Debug)]
+    pub struct IoError(std::io::Error);
+
+    impl std::fmt::Display for IoError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl embedded_io::Error for IoError {
+        fn kind(&self) -> ErrorKind {
+            match self.0.kind() {
+                std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+                std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+                std::io::ErrorKind::ConnectionRefused => ErrorKind::ConnectionRefused,
+                std::io::ErrorKind::ConnectionReset => ErrorKind::ConnectionReset,
+                std::io::ErrorKind::ConnectionAborted => ErrorKind::ConnectionAborted,
+                std::io::ErrorKind::NotConnected => ErrorKind::NotConnected,
+                std::io::ErrorKind::AddrInUse => ErrorKind::AddrInUse,
+                std::io::ErrorKind::AddrNotAvailable => ErrorKind::AddrNotAvailable,
+                std::io::ErrorKind::BrokenPipe => ErrorKind::BrokenPipe,
+                std::io::ErrorKind::AlreadyExists => ErrorKind::AlreadyExists,
+                std::io::ErrorKind::InvalidInput => ErrorKind::InvalidInput,
+                std::io::ErrorKind::InvalidData => ErrorKind::InvalidData,
+                std::io::ErrorKind::TimedOut => ErrorKind::TimedOut,
+                std::io::ErrorKind::WriteZero => ErrorKind::WriteZero,
+                std::io::ErrorKind::Interrupted => ErrorKind::Interrupted,
+                std::io::ErrorKind::Unsupported => ErrorKind::Unsupported,
+                std::io::ErrorKind::OutOfMemory => ErrorKind::OutOfMemory,
+                _ => ErrorKind::Other,
+            }
+        }
+    }
+
+    impl ErrorType for HidUart {
+        type Error = IoError;
+    }
+
+    impl Read for HidUart {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            std::io::Read::read(self, buf).map_err(IoError)
+        }
+    }
+
+    impl Write for HidUart {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            std::io::Write::write(self, buf).map_err(IoError)
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            std::io::Write::flush(self).map_err(IoError)
+        }
+    }
+
+    impl ReadReady for HidUart {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.bytes_available() > 0)
+        }
+    }
+}
+
+/// Default capacity, in bytes, of a freshly constructed [`RxBuffer`].
+const DEFAULT_RX_BUFFER_CAPACITY: usize = 4096;
+
+/// Heap-backed ring buffer holding bytes read from the device that the
+/// caller hasn't consumed yet, e.g. the tail of a HID report left over
+/// after a `read` call's destination slice filled up.
 struct RxBuffer {
-    start: u8,
-    len: u8,
-    data: [u8; INTERRUPT_REPORT_LENGTH],
+    capacity: usize,
+    data: VecDeque<u8>,
+    /// Total number of bytes ever evicted by [`write`](RxBuffer::write)
+    /// because the buffer was full (or had zero capacity) when they
+    /// arrived. Monotonically increasing; never reset automatically.
+    overflow_count: u64,
 }
 
 impl RxBuffer {
     fn new() -> Self {
+        Self::with_capacity(DEFAULT_RX_BUFFER_CAPACITY)
+    }
+
+    fn with_capacity(capacity: usize) -> Self {
         Self {
-            start: 0,
-            len: 0,
-            data: [0; INTERRUPT_REPORT_LENGTH],
+            capacity,
+            data: VecDeque::with_capacity(capacity),
+            overflow_count: 0,
         }
     }
 
-    fn read(&mut self, dest: &mut [u8]) -> usize {
-        if self.len == 0 {
-            return 0;
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.data.len() > capacity {
+            self.data.pop_front();
         }
+    }
 
-        let num_bytes_read = min(dest.len(), self.len as usize);
-        let start = self.start as usize;
-        let end = start + num_bytes_read;
-        let source_buf = &self.data[start..end];
-        dest[0..num_bytes_read].copy_from_slice(&source_buf);
-        self.len -= num_bytes_read as u8;
-        if self.len == 0 {
-            self.start = 0;
-        } else {
-            self.start += num_bytes_read as u8;
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    fn overflow_count(&self) -> u64 {
+        self.overflow_count
+    }
+
+    fn read(&mut self, dest: &mut [u8]) -> usize {
+        let num_bytes_read = min(dest.len(), self.data.len());
+        for slot in dest[0..num_bytes_read].iter_mut() {
+            *slot = self.data.pop_front().expect("checked against data.len()");
         }
 
         return num_bytes_read;
     }
 
     fn write(&mut self, source: &[u8]) {
-        if source.len() == 0 {
+        if self.capacity == 0 {
+            self.overflow_count += source.len() as u64;
             return;
         }
 
-        self.data[0..source.len()].copy_from_slice(&source);
-        self.start = 0;
-        self.len = source.len() as u8;
+        for &byte in source {
+            if self.data.len() >= self.capacity {
+                self.data.pop_front();
+                self.overflow_count += 1;
+            }
+            self.data.push_back(byte);
+        }
     }
 
     fn clear(&mut self) {
-        self.start = 0;
-        self.len = 0;
+        self.data.clear();
     }
 }
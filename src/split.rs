@@ -0,0 +1,115 @@
+//! Splitting a [`HidUart`] into independent reader/writer halves.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::*;
+use crate::gpio::{assert_de, deassert_de, wait_for_tx_drain};
+use crate::{read_report, read_report_nonblocking, write_report, HidUart, Rs485Config, RxBuffer};
+
+/// The writing half of a [`HidUart`] produced by [`HidUart::split`].
+pub struct UartTx {
+    handle: Arc<Mutex<hidapi::HidDevice>>,
+    write_timeout: Duration,
+    rs485: Option<Rs485Config>,
+}
+
+/// The receiving half of a [`HidUart`] produced by [`HidUart::split`].
+pub struct UartRx {
+    handle: Arc<Mutex<hidapi::HidDevice>>,
+    read_timeout: Duration,
+    rx_buffer: RxBuffer,
+}
+
+impl HidUart {
+    /// Splits this `HidUart` into an independent [`UartTx`] and [`UartRx`].
+    ///
+    /// The underlying `hidapi::HidDevice` is shared behind an `Arc<Mutex<_>>`
+    /// and locked only for the duration of a single HID report transfer, so
+    /// a reader task and a writer task can run on different threads without
+    /// serializing the whole device.
+    pub fn split(self) -> (UartTx, UartRx) {
+        let tx = UartTx {
+            handle: Arc::clone(&self.handle),
+            write_timeout: self.write_timeout,
+            rs485: self.rs485,
+        };
+        let rx = UartRx {
+            handle: self.handle,
+            read_timeout: self.read_timeout,
+            rx_buffer: self.rx_buffer,
+        };
+        (tx, rx)
+    }
+}
+
+impl UartTx {
+    /// Returns transmitting timeout.
+    pub fn write_timeout(&self) -> Duration {
+        self.write_timeout
+    }
+
+    /// Set transmitting timeout to `timeout` value.
+    pub fn set_write_timeout(&mut self, timeout: Duration) {
+        self.write_timeout = timeout;
+    }
+
+    /// Transmit `data`. See [`HidUart::write`].
+    ///
+    /// If the `HidUart` this half was split from had
+    /// [RS485 mode](HidUart::enable_rs485) enabled, the driver-enable GPIO
+    /// pin is asserted and deasserted around the transmission exactly as
+    /// [`HidUart::write`] does.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(config) = self.rs485 {
+            assert_de(&self.handle, &config)?;
+            let write_result = write_report(&self.handle, self.write_timeout, data);
+            let drain_result = wait_for_tx_drain(&self.handle, &config, self.write_timeout);
+            let deassert_result = deassert_de(&self.handle, &config);
+            return write_result.and(drain_result).and(deassert_result);
+        }
+
+        write_report(&self.handle, self.write_timeout, data)
+    }
+}
+
+impl UartRx {
+    /// Returns receiving timeout.
+    pub fn read_timeout(&self) -> Duration {
+        self.read_timeout
+    }
+
+    /// Set receiving timeout to `timeout` value.
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    /// Receive `data` and returns a number of read bytes. See
+    /// [`HidUart::read`].
+    pub fn read(&mut self, data: &mut [u8]) -> Result<usize> {
+        read_report(&self.handle, self.read_timeout, &mut self.rx_buffer, data)
+    }
+
+    /// Non-blocking read. See [`HidUart::read_nonblocking`].
+    pub fn read_nonblocking(&mut self, data: &mut [u8]) -> Result<usize> {
+        read_report_nonblocking(&self.handle, &mut self.rx_buffer, data)
+    }
+
+    /// Returns the number of bytes currently buffered and available to
+    /// read without blocking.
+    pub fn bytes_available(&self) -> usize {
+        self.rx_buffer.len()
+    }
+
+    /// Sets the capacity, in bytes, of the internal receive ring buffer.
+    /// See [`HidUart::set_rx_buffer_capacity`].
+    pub fn set_rx_buffer_capacity(&mut self, capacity: usize) {
+        self.rx_buffer.set_capacity(capacity);
+    }
+
+    /// Returns the number of bytes ever dropped from the receive ring
+    /// buffer. See [`HidUart::rx_overflow_count`].
+    pub fn rx_overflow_count(&self) -> u64 {
+        self.rx_buffer.overflow_count()
+    }
+}